@@ -0,0 +1,10 @@
+//! Core of the bootstrap build system.
+//!
+//! This crate is the entry point for the logic that drives `x.py`. It is
+//! split into a handful of modules under `utils` that provide the low-level
+//! building blocks (process execution, caching, ...) the rest of bootstrap
+//! is built on top of.
+
+pub mod utils;
+
+pub use utils::exec::{BehaviorOnFailure, BootstrapCommand, CommandOutput, OutputMode};
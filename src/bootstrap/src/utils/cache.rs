@@ -0,0 +1,71 @@
+//! A tiny string interner.
+//!
+//! Bootstrap re-checks the same handful of path lists (submodules, `git`
+//! diff filters, ...) constantly, and those checks end up as `HashMap` keys
+//! all over `ExecutionContext`. Interning the strings means those keys are
+//! cheap to clone and compare instead of re-hashing and re-allocating the
+//! same content over and over.
+
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+pub struct Interned<T: 'static>(&'static T);
+
+impl<T> Clone for Interned<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Interned<T> {}
+
+impl<T: PartialEq> PartialEq for Interned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0) || self.0 == other.0
+    }
+}
+
+impl<T: Eq> Eq for Interned<T> {}
+
+impl<T> Hash for Interned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state);
+    }
+}
+
+impl<T> Deref for Interned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Interned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub struct Interner {
+    strings: Mutex<HashSet<&'static String>>,
+}
+
+impl Interner {
+    const fn new() -> Self {
+        Self { strings: Mutex::new(HashSet::new()) }
+    }
+
+    pub fn intern_str(&self, s: &str) -> Interned<String> {
+        let mut strings = self.strings.lock().unwrap();
+        if let Some(existing) = strings.iter().find(|existing| existing.as_str() == s) {
+            return Interned(existing);
+        }
+        let leaked: &'static String = Box::leak(Box::new(s.to_owned()));
+        strings.insert(leaked);
+        Interned(leaked)
+    }
+}
+
+pub static INTERNER: Interner = Interner::new();
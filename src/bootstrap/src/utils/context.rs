@@ -1,26 +1,264 @@
 #![allow(dead_code)]
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::path::Path;
 
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use build_helper::ci::CiEnv;
 use build_helper::git::{GitConfig, PathFreshness};
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 
 use crate::CommandOutput;
 
 use super::cache::{Interned, INTERNER};
 use super::exec::{BehaviorOnFailure, BootstrapCommand, OutputMode};
 
+type EnvSnapshot = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+/// `(program, args, cwd, env)` - identifies a command for both the in-memory
+/// and on-disk caches.
+type DiskCacheKey = (PathBuf, Vec<Vec<u8>>, Option<PathBuf>, EnvSnapshot);
+
+/// How long a disk-cached command result may be reused for, and for how much
+/// longer after that a stale copy may still be served while a fresh run is
+/// kicked off in the background.
+#[derive(Clone, Copy, Debug)]
+pub struct CommandCacheConfig {
+    pub ttl: Duration,
+    pub stale: Duration,
+}
+
+impl CommandCacheConfig {
+    pub fn new(ttl: Duration, stale: Duration) -> Self {
+        Self { ttl, stale }
+    }
+}
+
+/// An on-disk snapshot of a previously captured `CommandOutput`, timestamped
+/// so it can be expired, and tagged with the key it was stored under so a
+/// hash collision on the cache path can't silently serve the wrong command's
+/// output.
+struct DiskCacheEntry {
+    key: DiskCacheKey,
+    captured_at: SystemTime,
+    output: CommandOutput,
+}
+
+impl DiskCacheEntry {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Self::encode_key(&mut buf, &self.key);
+        let secs = self.captured_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        buf.extend_from_slice(&secs.to_le_bytes());
+        match self.output.status_code() {
+            Some(code) => {
+                buf.push(1);
+                buf.extend_from_slice(&code.to_le_bytes());
+            }
+            None => buf.push(0),
+        }
+        Self::encode_bytes(&mut buf, self.output.raw_stdout());
+        Self::encode_bytes(&mut buf, self.output.raw_stderr());
+        buf
+    }
+
+    fn encode_bytes(buf: &mut Vec<u8>, data: Option<&[u8]>) {
+        match data {
+            Some(bytes) => {
+                buf.push(1);
+                buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+                buf.extend_from_slice(bytes);
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut cursor = buf;
+        let key = Self::take_key(&mut cursor)?;
+        let secs = Self::take_u64(&mut cursor)?;
+        let captured_at = UNIX_EPOCH + Duration::from_secs(secs);
+
+        let status_code = match Self::take_u8(&mut cursor)? {
+            1 => Some(Self::take_i32(&mut cursor)?),
+            _ => None,
+        };
+        let stdout = Self::take_bytes(&mut cursor)?;
+        let stderr = Self::take_bytes(&mut cursor)?;
+        Some(Self { key, captured_at, output: CommandOutput::from_raw_parts(status_code, stdout, stderr) })
+    }
+
+    fn encode_path(buf: &mut Vec<u8>, path: &Path) {
+        Self::encode_bytes(buf, Some(path.as_os_str().as_bytes()));
+    }
+
+    fn encode_opt_path(buf: &mut Vec<u8>, path: Option<&Path>) {
+        match path {
+            Some(p) => {
+                buf.push(1);
+                Self::encode_path(buf, p);
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn encode_key(buf: &mut Vec<u8>, key: &DiskCacheKey) {
+        let (program, args, cwd, env) = key;
+        Self::encode_path(buf, program);
+        buf.extend_from_slice(&(args.len() as u64).to_le_bytes());
+        for arg in args {
+            Self::encode_bytes(buf, Some(arg));
+        }
+        Self::encode_opt_path(buf, cwd.as_deref());
+        buf.extend_from_slice(&(env.len() as u64).to_le_bytes());
+        for (name, value) in env {
+            Self::encode_bytes(buf, Some(name));
+            Self::encode_bytes(buf, value.as_deref());
+        }
+    }
+
+    fn take_path(cursor: &mut &[u8]) -> Option<PathBuf> {
+        let bytes = Self::take_bytes(cursor)??;
+        Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+    }
+
+    fn take_opt_path(cursor: &mut &[u8]) -> Option<Option<PathBuf>> {
+        match Self::take_u8(cursor)? {
+            1 => Some(Some(Self::take_path(cursor)?)),
+            _ => Some(None),
+        }
+    }
+
+    fn take_key(cursor: &mut &[u8]) -> Option<DiskCacheKey> {
+        let program = Self::take_path(cursor)?;
+
+        let arg_count = Self::take_u64(cursor)? as usize;
+        let mut args = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            args.push(Self::take_bytes(cursor)??);
+        }
+
+        let cwd = Self::take_opt_path(cursor)?;
+
+        let env_count = Self::take_u64(cursor)? as usize;
+        let mut env = Vec::with_capacity(env_count);
+        for _ in 0..env_count {
+            let name = Self::take_bytes(cursor)??;
+            let value = Self::take_bytes(cursor)?;
+            env.push((name, value));
+        }
+
+        Some((program, args, cwd, env))
+    }
+
+    fn take_u8(cursor: &mut &[u8]) -> Option<u8> {
+        let (&byte, rest) = cursor.split_first()?;
+        *cursor = rest;
+        Some(byte)
+    }
+
+    fn take_u64(cursor: &mut &[u8]) -> Option<u64> {
+        if cursor.len() < 8 {
+            return None;
+        }
+        let (head, rest) = cursor.split_at(8);
+        *cursor = rest;
+        Some(u64::from_le_bytes(head.try_into().ok()?))
+    }
+
+    fn take_i32(cursor: &mut &[u8]) -> Option<i32> {
+        if cursor.len() < 4 {
+            return None;
+        }
+        let (head, rest) = cursor.split_at(4);
+        *cursor = rest;
+        Some(i32::from_le_bytes(head.try_into().ok()?))
+    }
+
+    fn take_bytes(cursor: &mut &[u8]) -> Option<Option<Vec<u8>>> {
+        match Self::take_u8(cursor)? {
+            1 => {
+                let len = Self::take_u64(cursor)? as usize;
+                if cursor.len() < len {
+                    return None;
+                }
+                let (head, rest) = cursor.split_at(len);
+                *cursor = rest;
+                Some(Some(head.to_vec()))
+            }
+            _ => Some(None),
+        }
+    }
+}
+
+/// A command whose failure was delayed rather than aborting the build right
+/// away, recorded so it can be surfaced in a single summary once bootstrap
+/// is done.
+#[derive(Clone, Debug)]
+pub struct FailedCommand {
+    pub program: PathBuf,
+    pub args: Vec<Vec<u8>>,
+    pub cwd: Option<PathBuf>,
+    pub status_code: Option<i32>,
+    pub stderr: Option<String>,
+}
+
+impl FailedCommand {
+    fn render(&self) -> String {
+        let mut rendered = self.program.display().to_string();
+        for arg in &self.args {
+            rendered.push(' ');
+            rendered.push_str(&String::from_utf8_lossy(arg));
+        }
+        if let Some(cwd) = &self.cwd {
+            rendered.push_str(&format!(" (in {})", cwd.display()));
+        }
+        match self.status_code {
+            Some(code) => rendered.push_str(&format!(" [exit code {}]", code)),
+            None => rendered.push_str(" [did not run]"),
+        }
+        rendered
+    }
+}
 
 pub struct ExecutionContext {
     dry_run: bool,
     verbose: usize,
     fail_fast: bool,
 
-    command_output_cache: Mutex<HashMap<(PathBuf, Vec<Vec<u8>>, Option<PathBuf>), Result<CommandOutput, String>>>,
+    /// Directory that the disk-backed command cache (see `run_cmd_cached`)
+    /// writes its entries under.
+    exec_cache_dir: PathBuf,
+    /// When set, disk cache reads are skipped, but results are still written
+    /// back to the disk cache. The in-memory cache of `run_cmd` still applies.
+    force_refresh_cache: bool,
+
+    /// Commands whose failure was delayed (`BehaviorOnFailure::DelayFail`,
+    /// or `Exit` without `fail_fast`), reported together by
+    /// `report_delayed_failures`. `Arc`-wrapped so background cache
+    /// revalidation threads (see `spawn_cache_revalidation`) can record into
+    /// it too.
+    delayed_failures: Arc<Mutex<Vec<FailedCommand>>>,
+
+    /// Background cache-revalidation threads spawned by `run_cmd_cached`
+    /// that haven't been joined yet. Joined by `report_delayed_failures` so
+    /// a pending disk-cache write can't be cut short by process exit.
+    pending_revalidations: Mutex<Vec<std::thread::JoinHandle<()>>>,
+
+    /// How long a burst of filesystem events must settle for before `watch`
+    /// treats it as done and fires `on_change`.
+    watch_debounce: Duration,
+    /// Directories under which `watch` ignores change events (generated/build
+    /// output that would otherwise retrigger itself). They are still watched;
+    /// their events are filtered out after debouncing.
+    watch_ignore_dirs: Vec<PathBuf>,
+
+    command_output_cache: Mutex<HashMap<(PathBuf, Vec<Vec<u8>>, Option<PathBuf>, EnvSnapshot), Result<CommandOutput, String>>>,
     file_contents_cache: Mutex<HashMap<PathBuf, std::io::Result<String>>>,
     path_exist_cache: Mutex<HashMap<PathBuf, bool>>,
     path_modifications_cache: Mutex<HashMap<(PathBuf, Interned<String>), PathFreshness>>
@@ -32,6 +270,12 @@ impl ExecutionContext {
             dry_run,
             verbose,
             fail_fast,
+            exec_cache_dir: PathBuf::from("build/cache/exec"),
+            force_refresh_cache: false,
+            delayed_failures: Arc::new(Mutex::new(Vec::new())),
+            pending_revalidations: Mutex::new(Vec::new()),
+            watch_debounce: Duration::from_millis(100),
+            watch_ignore_dirs: vec![PathBuf::from("build")],
             command_output_cache: Mutex::new(HashMap::new()),
             file_contents_cache: Mutex::new(HashMap::new()),
             path_exist_cache: Mutex::new(HashMap::new()),
@@ -39,6 +283,32 @@ impl ExecutionContext {
         }
     }
 
+    /// Overrides where the disk-backed command cache stores its entries.
+    /// Defaults to `build/cache/exec`.
+    pub fn set_exec_cache_dir(&mut self, dir: PathBuf) {
+        self.exec_cache_dir = dir;
+    }
+
+    /// When `force` is set, `run_cmd_cached` never serves a value from the
+    /// disk cache, though it still refreshes the disk entry with the result.
+    /// A command already run in this process is still served from the
+    /// in-memory cache of `run_cmd` rather than re-run.
+    pub fn set_force_refresh_cache(&mut self, force: bool) {
+        self.force_refresh_cache = force;
+    }
+
+    /// Overrides the debounce window `watch` waits for a burst of changes to
+    /// settle before firing. Defaults to 100ms.
+    pub fn set_watch_debounce(&mut self, debounce: Duration) {
+        self.watch_debounce = debounce;
+    }
+
+    /// Overrides the directories `watch` ignores changes under. Defaults to
+    /// `["build"]`.
+    pub fn set_watch_ignore_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.watch_ignore_dirs = dirs;
+    }
+
 
     fn execute_bootstrap_command_internal(&self, cmd: &mut BootstrapCommand, stdout_mode: OutputMode, stderr_mode: OutputMode) -> Result<CommandOutput, String> {
         if self.dry_run && !cmd.run_always {
@@ -53,7 +323,13 @@ impl ExecutionContext {
         command.stdout(stdout_mode.stdio());
         command.stderr(stderr_mode.stdio());
 
-        let output = match command.output() {
+        let spawn_result = if stdout_mode == OutputMode::Tee || stderr_mode == OutputMode::Tee {
+            super::exec::spawn_with_tee(command, stdout_mode, stderr_mode)
+        } else {
+            command.output()
+        };
+
+        let output = match spawn_result {
             Ok(output) => {
                 self.verbose_print(&format!("finished running {:?}", command));
                 CommandOutput::from_output(output, stdout_mode, stderr_mode)
@@ -92,16 +368,67 @@ impl ExecutionContext {
                     self.fatal_error(&format!("Exiting due to command failure: {:?}", cmd));
                 } else {
                     eprintln!("(Failure Delayed)");
+                    self.record_delayed_failure(cmd, output);
                 }
             }
             BehaviorOnFailure::DelayFail => {
                 eprintln!("(Failure delayed)");
+                self.record_delayed_failure(cmd, output);
             }
             BehaviorOnFailure::Ignore => {}
         }
 
     }
 
+    fn record_delayed_failure(&self, cmd: &BootstrapCommand, output: &CommandOutput) {
+        Self::push_delayed_failure(&self.delayed_failures, cmd, output);
+    }
+
+    fn push_delayed_failure(delayed_failures: &Mutex<Vec<FailedCommand>>, cmd: &BootstrapCommand, output: &CommandOutput) {
+        let command = cmd.as_command();
+        let program = PathBuf::from(command.get_program());
+        let args: Vec<Vec<u8>> = command.get_args().map(|a| a.as_bytes().to_vec()).collect();
+        let cwd = command.get_current_dir().map(|p| p.to_path_buf());
+
+        delayed_failures.lock().unwrap().push(FailedCommand {
+            program,
+            args,
+            cwd,
+            status_code: output.status_code(),
+            stderr: output.stderr_if_present(),
+        });
+    }
+
+    /// Prints a grouped summary of every command whose failure was delayed
+    /// rather than aborting the build immediately. Returns a nonzero exit
+    /// code if any such failures were recorded, so callers can propagate it
+    /// as the process's final exit status.
+    ///
+    /// Joins any outstanding cache-revalidation threads first, so a
+    /// revalidation that's still writing its result can't be killed
+    /// mid-write by the process exiting right after this returns.
+    pub fn report_delayed_failures(&self) -> i32 {
+        for handle in self.pending_revalidations.lock().unwrap().drain(..) {
+            let _ = handle.join();
+        }
+
+        let failures = self.delayed_failures.lock().unwrap();
+        if failures.is_empty() {
+            return 0;
+        }
+
+        eprintln!("\n{} command(s) failed and were delayed:", failures.len());
+        for failure in failures.iter() {
+            eprintln!("\n- {}", failure.render());
+            if let Some(stderr) = &failure.stderr {
+                for line in stderr.lines() {
+                    eprintln!("    {}", line);
+                }
+            }
+        }
+        1
+    }
+
 
     pub fn read_file(&mut self, path: &Path) -> String {
         let mut cache = self.file_contents_cache.lock().unwrap();
@@ -129,26 +456,247 @@ impl ExecutionContext {
         result
     }
 
-    pub fn run_cmd(&mut self, mut cmd: BootstrapCommand, stdout_mode: OutputMode, stderr_mode: OutputMode) -> Result<CommandOutput, String>{
-        let command_key = {
-            let command = cmd.as_command_mut();
-            let key_program = PathBuf::from(command.get_program());
-            let key_args: Vec<Vec<u8>> = command.get_args().map(|a| a.as_bytes().to_vec()).collect();
-            let key_cwd = command.get_current_dir().map(|p| p.to_path_buf());
-            (key_program, key_args, key_cwd)
+    /// Watches `paths` for changes and calls `on_change` every time a burst
+    /// of edits settles, forever - this is the engine behind an incremental
+    /// edit-build loop so contributors don't have to manually rerun
+    /// bootstrap after every change.
+    ///
+    /// Built on the OS's native filesystem notifications (inotify/kqueue/...
+    /// via `notify`) rather than polling, with events coalesced by
+    /// `notify-debouncer-mini` so a burst of edits within `watch_debounce`
+    /// of each other produces a single `on_change` call. Events under
+    /// `watch_ignore_dirs` (e.g. `build`) are filtered out before that, and
+    /// `file_contents_cache`/`path_exist_cache` entries for changed paths
+    /// are invalidated right before `on_change` runs so it sees fresh
+    /// content.
+    pub fn watch(&mut self, paths: &[PathBuf], mut on_change: impl FnMut(&mut Self)) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer = match new_debouncer(self.watch_debounce, move |result: DebounceEventResult| {
+            let _ = tx.send(result);
+        }) {
+            Ok(debouncer) => debouncer,
+            Err(e) => {
+                self.warn(&format!("failed to start filesystem watcher: {e}"));
+                return;
+            }
         };
 
+        for path in paths {
+            if let Err(e) = debouncer.watcher().watch(path, RecursiveMode::Recursive) {
+                self.warn(&format!("failed to watch {}: {e}", path.display()));
+            }
+        }
+
+        for result in rx {
+            let events = match result {
+                Ok(events) => events,
+                Err(errors) => {
+                    for error in errors {
+                        self.warn(&format!("watch error: {error:?}"));
+                    }
+                    continue;
+                }
+            };
+
+            let changed_paths: Vec<PathBuf> = events
+                .into_iter()
+                .map(|event| event.path)
+                .filter(|path| !self.is_watch_ignored(path))
+                .collect();
+
+            if changed_paths.is_empty() {
+                continue;
+            }
+
+            {
+                let mut file_contents_cache = self.file_contents_cache.lock().unwrap();
+                let mut path_exist_cache = self.path_exist_cache.lock().unwrap();
+                for path in &changed_paths {
+                    file_contents_cache.remove(path);
+                    path_exist_cache.remove(path);
+                }
+            }
+
+            self.verbose_print(&format!("detected changes in {} path(s), rebuilding", changed_paths.len()));
+            on_change(self);
+        }
+    }
+
+    /// True if `path` falls under any entry of `watch_ignore_dirs`. Ignore
+    /// entries may be given relative (the `"build"` default) even though
+    /// `path` - coming from a watch rooted at an absolute source directory,
+    /// the normal case - is absolute; a plain `path.starts_with(ignored)`
+    /// would never match that combination; so a relative `ignored` is also
+    /// tried resolved against the current directory.
+    fn is_watch_ignored(&self, path: &Path) -> bool {
+        self.watch_ignore_dirs.iter().any(|ignored| Self::path_is_under(path, ignored))
+    }
+
+    fn path_is_under(path: &Path, ignored: &Path) -> bool {
+        if path.starts_with(ignored) {
+            return true;
+        }
+        if ignored.is_relative() {
+            if let Ok(cwd) = std::env::current_dir() {
+                return path.starts_with(cwd.join(ignored));
+            }
+        }
+        false
+    }
+
+    pub fn run_cmd(&mut self, mut cmd: BootstrapCommand, stdout_mode: OutputMode, stderr_mode: OutputMode) -> Result<CommandOutput, String>{
+        let command_key = Self::command_cache_key(&mut cmd);
+
         let mut cache = self.command_output_cache.lock().unwrap();
         if let Some(cached_result) = cache.get(&command_key) {
             self.verbose_print(&format!("(cache) Running BootstrapCommand: {:?}", cmd));
             return cached_result.clone();
         }
+        drop(cache);
 
         let result = self.execute_bootstrap_command_internal(&mut cmd, stdout_mode, stderr_mode);
-        cache.insert(command_key.clone(), result.clone());
+        self.command_output_cache.lock().unwrap().insert(command_key, result.clone());
+        result
+    }
+
+    /// Runs `cmd` through the same in-memory cache as `run_cmd`, additionally
+    /// persisting the result to a file under `exec_cache_dir` named after a
+    /// hash of `(program, args, cwd, env)`.
+    ///
+    /// An entry younger than `cache.ttl` is returned without running the
+    /// command at all. An entry older than that but still within
+    /// `cache.ttl + cache.stale` is returned immediately (serving the stale
+    /// value, as in stale-while-revalidate), while a fresh run is kicked off
+    /// on a background thread to refresh the file for next time.
+    /// `set_force_refresh_cache(true)` bypasses disk cache reads (the
+    /// in-memory cache still applies), but the result is still written back
+    /// to disk.
+    pub fn run_cmd_cached(&mut self, mut cmd: BootstrapCommand, stdout_mode: OutputMode, stderr_mode: OutputMode, cache: CommandCacheConfig) -> Result<CommandOutput, String> {
+        let key = Self::command_cache_key(&mut cmd);
+        let cache_path = Self::disk_cache_path(&self.exec_cache_dir, &key);
+
+        if !self.force_refresh_cache {
+            if let Some(entry) = Self::read_disk_cache_entry(&cache_path, &key) {
+                let age = SystemTime::now().duration_since(entry.captured_at).unwrap_or(Duration::ZERO);
+                if age < cache.ttl {
+                    self.verbose_print(&format!("(disk cache) {:?}", cmd));
+                    return Ok(entry.output);
+                }
+                if age < cache.ttl + cache.stale {
+                    if self.dry_run && !cmd.run_always {
+                        self.verbose_print(&format!("(stale disk cache, dry run, not revalidating) {:?}", cmd));
+                    } else {
+                        self.verbose_print(&format!("(stale disk cache, revalidating) {:?}", cmd));
+                        self.spawn_cache_revalidation(cmd, stdout_mode, stderr_mode, key, cache_path);
+                    }
+                    return Ok(entry.output);
+                }
+            }
+        }
+
+        let result = self.run_cmd(cmd, stdout_mode, stderr_mode);
+        if let Ok(ref output) = result {
+            Self::write_disk_cache_entry(&cache_path, &key, output);
+        }
         result
     }
 
+    fn command_cache_key(cmd: &mut BootstrapCommand) -> DiskCacheKey {
+        let command = cmd.as_command_mut();
+        let key_program = PathBuf::from(command.get_program());
+        let key_args: Vec<Vec<u8>> = command.get_args().map(|a| a.as_bytes().to_vec()).collect();
+        let key_cwd = command.get_current_dir().map(|p| p.to_path_buf());
+        let key_env: EnvSnapshot = command
+            .get_envs()
+            .map(|(k, v)| (k.as_bytes().to_vec(), v.map(|v| v.as_bytes().to_vec())))
+            .collect();
+        (key_program, key_args, key_cwd, key_env)
+    }
+
+    fn disk_cache_path(cache_dir: &Path, key: &DiskCacheKey) -> PathBuf {
+        let (program, args, cwd, env) = key;
+
+        let mut hasher = DefaultHasher::new();
+        program.hash(&mut hasher);
+        args.hash(&mut hasher);
+        cwd.hash(&mut hasher);
+        env.hash(&mut hasher);
+
+        cache_dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Reads back the cache entry at `path`, but only if its stored key
+    /// still matches `expected_key` - the path is only a hash of the key, so
+    /// without this check a hash collision between two different commands
+    /// would silently serve one command's output for the other.
+    fn read_disk_cache_entry(path: &Path, expected_key: &DiskCacheKey) -> Option<DiskCacheEntry> {
+        let bytes = std::fs::read(path).ok()?;
+        let entry = DiskCacheEntry::decode(&bytes)?;
+        if &entry.key != expected_key {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Writes `output` to `path`, first under a sibling temp file and then
+    /// `rename`d into place, so a reader never observes a partially-written
+    /// entry and a process exiting mid-write can't truncate the real file.
+    fn write_disk_cache_entry(path: &Path, key: &DiskCacheKey, output: &CommandOutput) {
+        if let Some(parent) = path.parent() {
+            if std::fs::create_dir_all(parent).is_err() {
+                return;
+            }
+        }
+        let entry = DiskCacheEntry { key: key.clone(), captured_at: SystemTime::now(), output: output.clone() };
+        let tmp_path = path.with_file_name(format!(
+            "{}.tmp-{}-{:?}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id(),
+            std::thread::current().id(),
+        ));
+        if std::fs::write(&tmp_path, entry.encode()).is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+            return;
+        }
+        let _ = std::fs::rename(&tmp_path, path);
+    }
+
+    /// Re-runs `cmd` on a background thread and writes the fresh result to
+    /// `cache_path` once it completes, without blocking the caller. This is
+    /// the "revalidate" half of stale-while-revalidate: the caller already
+    /// got the stale value back and moved on. The thread is tracked in
+    /// `pending_revalidations` so `report_delayed_failures` can join it
+    /// before the process exits. A failure is recorded as a delayed failure
+    /// unless the command allows failure (`BehaviorOnFailure::Ignore`); an
+    /// `Exit` failure is recorded too, even under `fail_fast`, rather than
+    /// aborting the process from this thread.
+    fn spawn_cache_revalidation(&self, mut cmd: BootstrapCommand, stdout_mode: OutputMode, stderr_mode: OutputMode, key: DiskCacheKey, cache_path: PathBuf) {
+        let delayed_failures = Arc::clone(&self.delayed_failures);
+        let handle = std::thread::spawn(move || {
+            let record_failure = cmd.failure_behavior != BehaviorOnFailure::Ignore;
+            let command = cmd.as_command_mut();
+            command.stdout(stdout_mode.stdio());
+            command.stderr(stderr_mode.stdio());
+            match command.output() {
+                Ok(raw_output) => {
+                    let fresh = CommandOutput::from_output(raw_output, stdout_mode, stderr_mode);
+                    if fresh.is_failure() && record_failure {
+                        Self::push_delayed_failure(&delayed_failures, &cmd, &fresh);
+                    }
+                    Self::write_disk_cache_entry(&cache_path, &key, &fresh);
+                }
+                Err(e) => {
+                    eprintln!("failed to execute {:?} while revalidating the command cache: {}", cmd, e);
+                    if record_failure {
+                        let output = CommandOutput::did_not_start(stdout_mode, stderr_mode);
+                        Self::push_delayed_failure(&delayed_failures, &cmd, &output);
+                    }
+                }
+            }
+        });
+        self.pending_revalidations.lock().unwrap().push(handle);
+    }
+
 
     pub fn check_path_modifications<'a> (&'a mut self, src_dir: &Path, git_config: &GitConfig<'a>, paths: &[&'static str]) -> PathFreshness {
 
@@ -224,4 +772,256 @@ impl ExecutionContext {
 
         Ok(!output.is_success())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_key(program: &str) -> DiskCacheKey {
+        (
+            PathBuf::from(program),
+            vec![b"--flag".to_vec(), b"value".to_vec()],
+            Some(PathBuf::from("/tmp/some/cwd")),
+            vec![(b"PATH".to_vec(), Some(b"/usr/bin".to_vec())), (b"UNSET".to_vec(), None)],
+        )
+    }
+
+    fn unique_cache_dir(label: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "bootstrap-cache-test-{label}-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ))
+    }
+
+    fn seed_stale_cache_entry(cache_dir: &Path, key: &DiskCacheKey, ttl: Duration) -> PathBuf {
+        std::fs::create_dir_all(cache_dir).unwrap();
+        let path = ExecutionContext::disk_cache_path(cache_dir, key);
+        let captured_at = SystemTime::now() - ttl - Duration::from_millis(1);
+        let entry = DiskCacheEntry { key: key.clone(), captured_at, output: CommandOutput::from_raw_parts(Some(0), None, None) };
+        std::fs::write(&path, entry.encode()).unwrap();
+        path
+    }
+
+    #[test]
+    fn run_cmd_cached_does_not_revalidate_in_a_dry_run() {
+        let cache_dir = unique_cache_dir("dry-run-skips");
+        let mut key_cmd = BootstrapCommand::new("echo");
+        key_cmd.args(["dry-run-skips-revalidation"]);
+        let key = ExecutionContext::command_cache_key(&mut key_cmd);
+        seed_stale_cache_entry(&cache_dir, &key, Duration::from_secs(1));
+
+        let mut ctx = ExecutionContext::new(true, 0, false);
+        ctx.set_exec_cache_dir(cache_dir.clone());
+        let mut cmd = BootstrapCommand::new("echo");
+        cmd.args(["dry-run-skips-revalidation"]);
+        let cache = CommandCacheConfig::new(Duration::from_secs(1), Duration::from_secs(60));
+
+        let result = ctx.run_cmd_cached(cmd, OutputMode::Capture, OutputMode::Capture, cache);
+
+        assert!(result.is_ok());
+        assert!(ctx.pending_revalidations.lock().unwrap().is_empty(), "dry run must not spawn a real subprocess to revalidate the cache");
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn run_cmd_cached_still_revalidates_a_run_always_command_in_a_dry_run() {
+        let cache_dir = unique_cache_dir("dry-run-run-always");
+        let mut key_cmd = BootstrapCommand::new("echo");
+        key_cmd.args(["dry-run-run-always-revalidates"]);
+        key_cmd.run_always();
+        let key = ExecutionContext::command_cache_key(&mut key_cmd);
+        seed_stale_cache_entry(&cache_dir, &key, Duration::from_secs(1));
+
+        let mut ctx = ExecutionContext::new(true, 0, false);
+        ctx.set_exec_cache_dir(cache_dir.clone());
+        let mut cmd = BootstrapCommand::new("echo");
+        cmd.args(["dry-run-run-always-revalidates"]);
+        cmd.run_always();
+        let cache = CommandCacheConfig::new(Duration::from_secs(1), Duration::from_secs(60));
+
+        ctx.run_cmd_cached(cmd, OutputMode::Capture, OutputMode::Capture, cache);
+
+        assert_eq!(ctx.pending_revalidations.lock().unwrap().len(), 1, "a run_always command must be revalidated even in a dry run");
+        assert_eq!(ctx.report_delayed_failures(), 0, "the real `echo` should have succeeded");
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn disk_cache_entry_roundtrips_through_encode_decode() {
+        let key = sample_key("rustc");
+        let output = CommandOutput::from_raw_parts(Some(0), Some(b"stdout bytes".to_vec()), Some(b"stderr bytes".to_vec()));
+        let entry = DiskCacheEntry { key: key.clone(), captured_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000), output };
+
+        let decoded = DiskCacheEntry::decode(&entry.encode()).expect("well-formed buffer should decode");
+
+        assert_eq!(decoded.key, key);
+        assert_eq!(decoded.captured_at, entry.captured_at);
+        assert_eq!(decoded.output.status_code(), Some(0));
+        assert_eq!(decoded.output.raw_stdout(), Some(&b"stdout bytes"[..]));
+        assert_eq!(decoded.output.raw_stderr(), Some(&b"stderr bytes"[..]));
+    }
+
+    #[test]
+    fn disk_cache_entry_roundtrips_missing_status_and_streams() {
+        let key = sample_key("cargo");
+        let output = CommandOutput::from_raw_parts(None, None, None);
+        let entry = DiskCacheEntry { key: key.clone(), captured_at: UNIX_EPOCH, output };
+
+        let decoded = DiskCacheEntry::decode(&entry.encode()).expect("well-formed buffer should decode");
+
+        assert_eq!(decoded.key, key);
+        assert_eq!(decoded.output.status_code(), None);
+        assert_eq!(decoded.output.raw_stdout(), None);
+        assert_eq!(decoded.output.raw_stderr(), None);
+    }
+
+    #[test]
+    fn decode_rejects_truncated_buffer() {
+        let key = sample_key("rustc");
+        let output = CommandOutput::from_raw_parts(Some(0), Some(b"hello".to_vec()), None);
+        let entry = DiskCacheEntry { key, captured_at: UNIX_EPOCH, output };
+
+        let mut encoded = entry.encode();
+        encoded.truncate(encoded.len() - 1);
+
+        assert!(DiskCacheEntry::decode(&encoded).is_none());
+    }
+
+    #[test]
+    fn disk_cache_path_is_stable_for_the_same_key_and_differs_for_others() {
+        let cache_dir = PathBuf::from("build/cache/exec");
+        let key_a = sample_key("rustc");
+        let key_b = sample_key("cargo");
+
+        assert_eq!(
+            ExecutionContext::disk_cache_path(&cache_dir, &key_a),
+            ExecutionContext::disk_cache_path(&cache_dir, &key_a)
+        );
+        assert_ne!(
+            ExecutionContext::disk_cache_path(&cache_dir, &key_a),
+            ExecutionContext::disk_cache_path(&cache_dir, &key_b)
+        );
+    }
+
+    #[test]
+    fn read_disk_cache_entry_detects_key_mismatch() {
+        let dir = std::env::temp_dir().join(format!(
+            "bootstrap-cache-test-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = sample_key("rustc");
+        let other_key = sample_key("cargo");
+        let path = ExecutionContext::disk_cache_path(&dir, &key);
+        let output = CommandOutput::from_raw_parts(Some(0), None, None);
+
+        ExecutionContext::write_disk_cache_entry(&path, &key, &output);
+
+        assert!(ExecutionContext::read_disk_cache_entry(&path, &key).is_some());
+        assert!(ExecutionContext::read_disk_cache_entry(&path, &other_key).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_command_render_includes_program_args_cwd_and_exit_code() {
+        let failure = FailedCommand {
+            program: PathBuf::from("rustc"),
+            args: vec![b"--edition".to_vec(), b"2021".to_vec()],
+            cwd: Some(PathBuf::from("/build/stage0")),
+            status_code: Some(1),
+            stderr: None,
+        };
+
+        assert_eq!(failure.render(), "rustc --edition 2021 (in /build/stage0) [exit code 1]");
+    }
+
+    #[test]
+    fn failed_command_render_reports_commands_that_never_ran() {
+        let failure = FailedCommand {
+            program: PathBuf::from("rustc"),
+            args: vec![],
+            cwd: None,
+            status_code: None,
+            stderr: None,
+        };
+
+        assert_eq!(failure.render(), "rustc [did not run]");
+    }
+
+    #[test]
+    fn report_delayed_failures_returns_zero_when_nothing_was_delayed() {
+        let ctx = ExecutionContext::new(false, 0, false);
+        assert_eq!(ctx.report_delayed_failures(), 0);
+    }
+
+    #[test]
+    fn report_delayed_failures_returns_nonzero_once_a_failure_is_recorded() {
+        let ctx = ExecutionContext::new(false, 0, false);
+        ctx.delayed_failures.lock().unwrap().push(FailedCommand {
+            program: PathBuf::from("cargo"),
+            args: vec![b"build".to_vec()],
+            cwd: None,
+            status_code: Some(101),
+            stderr: None,
+        });
+
+        assert_eq!(ctx.report_delayed_failures(), 1);
+    }
+
+    #[test]
+    fn report_delayed_failures_joins_pending_revalidation_threads() {
+        let ctx = ExecutionContext::new(false, 0, false);
+        let joined = Arc::new(Mutex::new(false));
+        let joined_in_thread = Arc::clone(&joined);
+        let handle = std::thread::spawn(move || {
+            *joined_in_thread.lock().unwrap() = true;
+        });
+        ctx.pending_revalidations.lock().unwrap().push(handle);
+
+        ctx.report_delayed_failures();
+
+        assert!(*joined.lock().unwrap(), "pending revalidation thread should be joined before reporting");
+        assert!(ctx.pending_revalidations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn path_is_under_matches_relative_ignore_dir_against_absolute_path() {
+        let cwd = std::env::current_dir().unwrap();
+        let ignored = PathBuf::from("build");
+        let path = cwd.join("build").join("stage0").join("bin").join("rustc");
+
+        assert!(ExecutionContext::path_is_under(&path, &ignored));
+    }
+
+    #[test]
+    fn path_is_under_matches_when_both_sides_are_absolute() {
+        let ignored = PathBuf::from("/checkout/build");
+        let path = PathBuf::from("/checkout/build/stage0/bin/rustc");
+
+        assert!(ExecutionContext::path_is_under(&path, &ignored));
+    }
+
+    #[test]
+    fn path_is_under_does_not_match_unrelated_paths() {
+        let ignored = PathBuf::from("build");
+        let path = PathBuf::from("/checkout/src/bootstrap/src/lib.rs");
+
+        assert!(!ExecutionContext::path_is_under(&path, &ignored));
+    }
+
+    #[test]
+    fn is_watch_ignored_checks_every_configured_ignore_dir() {
+        let mut ctx = ExecutionContext::new(false, 0, false);
+        let cwd = std::env::current_dir().unwrap();
+        ctx.set_watch_ignore_dirs(vec![PathBuf::from("build"), PathBuf::from("target")]);
+
+        assert!(ctx.is_watch_ignored(&cwd.join("target").join("debug").join("bootstrap")));
+        assert!(!ctx.is_watch_ignored(&cwd.join("src").join("bootstrap").join("src").join("lib.rs")));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,297 @@
+//! Low-level process execution primitives used by `ExecutionContext`.
+//!
+//! `BootstrapCommand` wraps `std::process::Command` with the extra metadata
+//! bootstrap needs to decide how to react to a failing command (exit
+//! immediately, delay the failure, or ignore it entirely), while
+//! `CommandOutput` captures what actually happened so callers can inspect,
+//! cache, or log it after the fact.
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{ExitStatus, Output, Stdio};
+
+/// What should happen when a command fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BehaviorOnFailure {
+    /// Abort the whole bootstrap invocation, unless `fail_fast` is disabled,
+    /// in which case the failure is recorded and reported at the end.
+    Exit,
+    /// Keep going, but remember that this command failed so it can be
+    /// reported once the build finishes.
+    DelayFail,
+    /// Don't even remember that this command failed.
+    Ignore,
+}
+
+/// Controls whether a command's stdout/stderr are shown to the user,
+/// captured for inspection by the caller, or both at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Forward the stream straight to bootstrap's own stdout/stderr.
+    Print,
+    /// Swallow the stream and make it available on the returned `CommandOutput`.
+    Capture,
+    /// Forward the stream to the user as it arrives, line by line, while
+    /// also accumulating it for the returned `CommandOutput` - useful for
+    /// long-running steps where users want live progress but callers (e.g.
+    /// `handle_failure`, the command cache) still need the captured text.
+    Tee,
+}
+
+impl OutputMode {
+    pub fn stdio(self) -> Stdio {
+        match self {
+            OutputMode::Print => Stdio::inherit(),
+            OutputMode::Capture | OutputMode::Tee => Stdio::piped(),
+        }
+    }
+
+    fn captures(self) -> bool {
+        matches!(self, OutputMode::Capture | OutputMode::Tee)
+    }
+}
+
+/// Runs `command`, forwarding each line of stdout/stderr to the terminal as
+/// it arrives for whichever of `stdout_mode`/`stderr_mode` is `Tee` or
+/// `Print` (a `Capture` stream stays silent), while
+/// accumulating the full bytes of both streams into the returned `Output`
+/// regardless of mode.
+pub(crate) fn spawn_with_tee(
+    command: &mut std::process::Command,
+    stdout_mode: OutputMode,
+    stderr_mode: OutputMode,
+) -> std::io::Result<Output> {
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let mut child = command.spawn()?;
+    let stdout_pipe = child.stdout.take().expect("stdout was piped");
+    let stderr_pipe = child.stderr.take().expect("stderr was piped");
+
+    let stdout_thread = std::thread::spawn(move || tee_stream(stdout_pipe, stdout_mode, std::io::stdout()));
+    let stderr_thread = std::thread::spawn(move || tee_stream(stderr_pipe, stderr_mode, std::io::stderr()));
+
+    let status = child.wait()?;
+    let stdout = stdout_thread.join().unwrap_or_default();
+    let stderr = stderr_thread.join().unwrap_or_default();
+
+    Ok(Output { status, stdout, stderr })
+}
+
+fn tee_stream<R: std::io::Read, W: std::io::Write>(pipe: R, mode: OutputMode, mut forward_to: W) -> Vec<u8> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut collected = Vec::new();
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                // `Capture` is the only mode that should stay silent - `Print`
+                // still needs to reach the terminal even though this stream
+                // only goes through a pipe (and line-buffering) because the
+                // *other* stream is `Tee`.
+                if mode != OutputMode::Capture {
+                    let _ = forward_to.write_all(&line);
+                }
+                collected.extend_from_slice(&line);
+            }
+        }
+    }
+    collected
+}
+
+/// A `std::process::Command` plus the bookkeeping bootstrap needs around it.
+#[derive(Debug)]
+pub struct BootstrapCommand {
+    command: std::process::Command,
+    pub(crate) failure_behavior: BehaviorOnFailure,
+    pub(crate) run_always: bool,
+    executed: bool,
+}
+
+impl BootstrapCommand {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            command: std::process::Command::new(program),
+            failure_behavior: BehaviorOnFailure::Exit,
+            run_always: false,
+            executed: false,
+        }
+    }
+
+    pub fn current_dir(&mut self, dir: &Path) -> &mut Self {
+        self.command.current_dir(dir);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(args);
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.command.env(key, value);
+        self
+    }
+
+    /// Don't treat a nonzero exit status as a fatal error.
+    pub fn allow_failure(mut self) -> Self {
+        self.failure_behavior = BehaviorOnFailure::Ignore;
+        self
+    }
+
+    /// Delay reporting a failure until the end of the build instead of
+    /// exiting immediately.
+    pub fn delay_failure(mut self) -> Self {
+        self.failure_behavior = BehaviorOnFailure::DelayFail;
+        self
+    }
+
+    /// Run this command even during a dry run.
+    pub fn run_always(&mut self) -> &mut Self {
+        self.run_always = true;
+        self
+    }
+
+    pub fn as_command_mut(&mut self) -> &mut std::process::Command {
+        &mut self.command
+    }
+
+    pub fn as_command(&self) -> &std::process::Command {
+        &self.command
+    }
+
+    pub(crate) fn mark_as_executed(&mut self) {
+        self.executed = true;
+    }
+}
+
+/// What came out of running a `BootstrapCommand`.
+#[derive(Clone, Debug, Default)]
+pub struct CommandOutput {
+    status: Option<ExitStatus>,
+    stdout: Option<Vec<u8>>,
+    stderr: Option<Vec<u8>>,
+}
+
+impl CommandOutput {
+    pub fn from_output(output: Output, stdout_mode: OutputMode, stderr_mode: OutputMode) -> Self {
+        Self {
+            status: Some(output.status),
+            stdout: stdout_mode.captures().then_some(output.stdout),
+            stderr: stderr_mode.captures().then_some(output.stderr),
+        }
+    }
+
+    /// Used when the process could not even be spawned.
+    pub fn did_not_start(_stdout_mode: OutputMode, _stderr_mode: OutputMode) -> Self {
+        Self { status: None, stdout: None, stderr: None }
+    }
+
+    /// Reconstructs an output from its raw parts, e.g. when loading one back
+    /// out of the on-disk command cache.
+    pub fn from_raw_parts(status_code: Option<i32>, stdout: Option<Vec<u8>>, stderr: Option<Vec<u8>>) -> Self {
+        Self { status: status_code.map(exit_status_from_code), stdout, stderr }
+    }
+
+    pub fn status_code(&self) -> Option<i32> {
+        self.status.and_then(|s| s.code())
+    }
+
+    pub fn raw_stdout(&self) -> Option<&[u8]> {
+        self.stdout.as_deref()
+    }
+
+    pub fn raw_stderr(&self) -> Option<&[u8]> {
+        self.stderr.as_deref()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status.is_some_and(|s| s.success())
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !self.is_success()
+    }
+
+    pub fn stderr_if_present(&self) -> Option<String> {
+        self.stderr.as_ref().map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+
+    pub fn stdout_if_present(&self) -> Option<String> {
+        self.stdout.as_ref().map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+}
+
+#[cfg(unix)]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn tee_stream_forwards_print_and_tee_but_stays_silent_for_capture() {
+        let input = b"line one\nline two\n";
+
+        for mode in [OutputMode::Print, OutputMode::Tee, OutputMode::Capture] {
+            let mut forwarded = Vec::new();
+            let collected = tee_stream(Cursor::new(input.to_vec()), mode, &mut forwarded);
+
+            assert_eq!(collected, input, "{mode:?} should always accumulate the full stream");
+            if mode == OutputMode::Capture {
+                assert!(forwarded.is_empty(), "Capture must not forward to the terminal");
+            } else {
+                assert_eq!(forwarded, input, "{mode:?} must forward every line it reads");
+            }
+        }
+    }
+
+    /// Regression test for a bug where `spawn_with_tee` piped both streams
+    /// unconditionally, but `tee_stream` only forwarded `Tee`-mode output -
+    /// so a `Print`-mode stream paired with a `Tee`-mode stream on the other
+    /// fd was read off its pipe and silently dropped, never reaching the
+    /// terminal and never being captured either.
+    #[test]
+    fn spawn_with_tee_captures_both_streams_under_every_mode_combination() {
+        let combinations = [
+            (OutputMode::Tee, OutputMode::Print),
+            (OutputMode::Print, OutputMode::Tee),
+            (OutputMode::Tee, OutputMode::Capture),
+            (OutputMode::Capture, OutputMode::Tee),
+            (OutputMode::Tee, OutputMode::Tee),
+        ];
+
+        for (stdout_mode, stderr_mode) in combinations {
+            let mut command = std::process::Command::new("sh");
+            command.args(["-c", "echo out; echo err 1>&2"]);
+
+            let output = spawn_with_tee(&mut command, stdout_mode, stderr_mode)
+                .unwrap_or_else(|e| panic!("failed to spawn under ({stdout_mode:?}, {stderr_mode:?}): {e}"));
+
+            assert_eq!(output.stdout, b"out\n", "stdout must be accumulated regardless of mode ({stdout_mode:?}, {stderr_mode:?})");
+            assert_eq!(output.stderr, b"err\n", "stderr must be accumulated regardless of mode ({stdout_mode:?}, {stderr_mode:?})");
+
+            let result = CommandOutput::from_output(output, stdout_mode, stderr_mode);
+            assert_eq!(result.raw_stdout().is_some(), stdout_mode.captures());
+            assert_eq!(result.raw_stderr().is_some(), stderr_mode.captures());
+        }
+    }
+}
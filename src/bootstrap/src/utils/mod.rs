@@ -0,0 +1,3 @@
+pub mod cache;
+pub mod context;
+pub mod exec;